@@ -0,0 +1,47 @@
+//! Compares `count_lines` with a byte at a time count of the same file.
+//!
+//! Run with `cargo bench --bench count_lines`. This is a plain timing loop
+//! rather than a statistical harness, so treat the numbers as a rough
+//! guide and run it a few times.
+
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use termease::{count_lines, mktemp_dir};
+
+const ROUNDS: u32 = 20;
+
+/// The count a simple loop gets, looking at every byte of the file
+fn naive_count(path: &str) -> usize {
+    fs::read(path).unwrap().iter().filter(|&&byte| byte == b'\n').count()
+}
+
+/// The best of several runs of `f`, which is the least disturbed by noise
+fn best_of(mut f: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut result = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        result = black_box(f());
+        best = best.min(start.elapsed());
+    }
+    (best, result)
+}
+
+fn main() {
+    let tmp = mktemp_dir().unwrap();
+    let path = tmp.path().join("lines.txt");
+    let line = "a line of text of a fairly typical length for a log file\n";
+    fs::write(&path, line.repeat(1_000_000)).unwrap();
+    let path = path.to_str().unwrap();
+
+    let (naive, expected) = best_of(|| naive_count(path));
+    let (fast, counted) = best_of(|| count_lines(path).unwrap());
+    assert_eq!(counted, expected);
+
+    let size = fs::metadata(path).unwrap().len() as f64 / (1024.0 * 1024.0);
+    println!("{:.0} MiB, {} lines", size, counted);
+    println!("byte at a time: {:?}", naive);
+    println!("count_lines:    {:?} ({:.1}x)", fast, naive.as_secs_f64() / fast.as_secs_f64());
+}
@@ -0,0 +1,58 @@
+//! Times `cp_buffered` over a range of buffer sizes against `cp`.
+//!
+//! Run with `cargo bench --bench cp_buffered`. Every copy is checked
+//! against the source, so this doubles as a check that the buffer size
+//! doesn't change what gets copied. As with the other benches the numbers
+//! are a rough guide, not a statistical result.
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use termease::{cp, cp_buffered, mktemp_dir};
+
+const ROUNDS: u32 = 5;
+
+/// The best of several runs of `f`, which is the least disturbed by noise
+fn best_of(mut f: impl FnMut()) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+    best
+}
+
+fn main() {
+    let tmp = mktemp_dir().unwrap();
+    let src = tmp.path().join("src");
+    let data: Vec<u8> = (0..32u32 << 20).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+    fs::write(&src, &data).unwrap();
+    let src = src.to_str().unwrap();
+    let dst = tmp.path().join("dst");
+    let dst = dst.to_str().unwrap();
+
+    println!("{} MiB", data.len() >> 20);
+    let time = best_of(|| {
+        cp(src, dst).unwrap();
+    });
+    assert_eq!(fs::read(dst).unwrap(), data);
+    println!("cp:                  {:?}", time);
+
+    for buf_size in [512, 4 << 10, 64 << 10, 128 << 10, 1 << 20, 8 << 20] {
+        let time = best_of(|| {
+            cp_buffered(src, dst, buf_size).unwrap();
+        });
+        assert_eq!(fs::read(dst).unwrap(), data, "buffer of {}", buf_size);
+        println!("cp_buffered {:>7}: {:?}", buf_size, time);
+    }
+
+    // a byte at a time is far too slow for the full file, so only check it
+    let small = &data[..64 << 10];
+    fs::write(src, small).unwrap();
+    let time = best_of(|| {
+        cp_buffered(src, dst, 1).unwrap();
+    });
+    assert_eq!(fs::read(dst).unwrap(), small);
+    println!("cp_buffered 1 (64 KiB): {:?}", time);
+}